@@ -3,9 +3,34 @@
 
 use core::{arch::asm, mem::size_of, ops::Range};
 
+#[cfg(feature = "arm-stack-limit")]
+pub mod stack_limit;
+
+#[cfg(feature = "mpu-guard")]
+pub mod mpu_guard;
+
 /// The value used to paint the stack.
 pub const STACK_PAINT_VALUE: u32 = 0xCCCC_CCCC;
 
+/// Writes [STACK_PAINT_VALUE] over every word from `from` (inclusive) up to `to`
+/// (exclusive), walking upwards. `from` must be less than or equal to `to`.
+#[inline(never)]
+fn paint_range(from: *mut u32, to: *mut u32) {
+    unsafe {
+        asm!(
+            "0:",
+            "cmp r2, r0",
+            "bls 1f",
+            "stmia r0!, {{r1}}",
+            "b 0b",
+            "1:",
+            in("r0") from,
+            in("r1") STACK_PAINT_VALUE,
+            in("r2") to,
+        )
+    };
+}
+
 /// The [Range] currently in use for the stack.
 ///
 /// Note: the stack is defined in reverse, as it runs from 'start' to 'end' downwards.
@@ -39,18 +64,163 @@ pub fn current_stack_ptr() -> *mut u32 {
     res
 }
 
+/// An explicitly supplied stack memory range to monitor, for use with stacks other than
+/// the one currently executing — for example a suspended RTOS task's stack, whose saved
+/// stack pointer is known at context-switch time.
+///
+/// Uses the same reversed convention as [stack()]: `range.start` is the top of the stack
+/// (high address) and `range.end` is the bottom (low address), the direction the stack
+/// grows towards.
+///
+/// [stack_size], [current_stack_in_use], [current_stack_free], [repaint_stack],
+/// [stack_painted] and [stack_painted_binary] are thin wrappers around a `StackRegion`
+/// built from [stack()] and [current_stack_ptr()]; reach for `StackRegion` directly when
+/// monitoring a stack that isn't the live one.
+pub struct StackRegion {
+    range: Range<*mut u32>,
+}
+
+impl StackRegion {
+    /// Constructs a region spanning `range`, using the reversed (`start >= end`)
+    /// convention documented on [StackRegion].
+    #[inline]
+    pub const fn new(range: Range<*mut u32>) -> Self {
+        Self { range }
+    }
+
+    /// The number of bytes reserved for this region.
+    #[inline]
+    pub const fn size(&self) -> u32 {
+        // Safety: start >= end, by the convention documented on StackRegion.
+        (unsafe { self.range.start.byte_offset_from_unsigned(self.range.end) }) as u32
+    }
+
+    /// The number of bytes of this region that are in use, given the stack pointer `sp`
+    /// saved for it.
+    ///
+    /// # Safety
+    /// `sp` must be a pointer into this region, i.e. `self.range.end <= sp <=
+    /// self.range.start`. A `sp` outside this region makes the pointer arithmetic below
+    /// Undefined Behaviour, not just a wrong result.
+    #[inline]
+    pub unsafe fn in_use(&self, sp: *mut u32) -> u32 {
+        // Safety: start >= sp, upheld by the caller per this function's Safety contract.
+        (unsafe { self.range.start.byte_offset_from_unsigned(sp) }) as u32
+    }
+
+    /// The number of bytes of this region that are free, given the stack pointer `sp`
+    /// saved for it.
+    ///
+    /// If the region has overflowed, this returns 0.
+    ///
+    /// # Safety
+    /// `sp` must be a pointer into this region, i.e. `self.range.end <= sp <=
+    /// self.range.start`. See [in_use](Self::in_use).
+    #[inline]
+    pub unsafe fn free(&self, sp: *mut u32) -> u32 {
+        self.size().saturating_sub(unsafe { self.in_use(sp) })
+    }
+
+    /// Paints the part of this region that is not in use, given the stack pointer `sp`
+    /// saved for it.
+    ///
+    /// **Note:** this can take some time, and if `sp` belongs to the currently active
+    /// stack an ISR could interrupt this process, dirtying up the freshly painted region.
+    /// If you wish to prevent this, run this inside a critical section using
+    /// [cortex_m::interrupt::free].
+    ///
+    /// Runs in *O(n)* where *n* is the size of this region.
+    /// This function is inefficient in the sense that it repaints the entire unused part,
+    /// even the parts that still have the [STACK_PAINT_VALUE].
+    ///
+    /// # Safety
+    /// `sp` must be a pointer into this region, i.e. `self.range.end <= sp <=
+    /// self.range.start`. A `sp` above `self.range.start` turns this into an unbounded,
+    /// out-of-bounds write.
+    #[inline(never)]
+    pub unsafe fn paint(&self, sp: *mut u32) {
+        paint_range(self.range.end, sp);
+    }
+
+    /// Finds the number of bytes that have not been overwritten in this region since the
+    /// last [paint](Self::paint), given the stack pointer `sp` saved for it.
+    ///
+    /// In other words: shows the worst case free space since [paint](Self::paint) was
+    /// last called.
+    ///
+    /// Runs in *O(n)* where *n* is the size of this region.
+    ///
+    /// # Safety
+    /// `sp` must be a pointer into this region, i.e. `self.range.end <= sp <=
+    /// self.range.start`.
+    pub unsafe fn painted(&self, sp: *mut u32) -> u32 {
+        let res: *const u32;
+        unsafe {
+            asm!(
+                "0:",
+                "cmp {sp}, {ptr}",
+                "bls 1f",
+                "ldr {value}, [{ptr}]",
+                "cmp {value}, {paint}",
+                "bne 1f",
+                "add {ptr}, #4",
+                "b 0b",
+                "1:",
+                sp = in(reg) sp,
+                ptr = inout(reg) self.range.end => res,
+                value = out(reg) _,
+                paint = in(reg) STACK_PAINT_VALUE,
+                options(nostack, readonly)
+            )
+        };
+        // Safety: res >= range.end because we start at range.end
+        (unsafe { res.byte_offset_from_unsigned(self.range.end) }) as u32
+    }
+
+    /// Finds the number of bytes that have not been overwritten in this region since the
+    /// last [paint](Self::paint) using binary search, given the stack pointer `sp` saved
+    /// for it.
+    ///
+    /// In other words: shows the worst case free space since [paint](Self::paint) was
+    /// last called.
+    ///
+    /// Uses binary search to find the point after which the region is written.
+    /// This will assume that the region is written in a consecutive fashion.
+    /// Writing somewhere out-of-order into the painted region will not be detected.
+    ///
+    /// Runs in *O(log(n))* where *n* is the size of this region.
+    ///
+    /// **Danger:** if the part of this region below `sp` that was written since the last
+    /// [paint](Self::paint) contains the [STACK_PAINT_VALUE] this computation may be very
+    /// incorrect.
+    ///
+    /// # Safety
+    /// `sp` must be a pointer into this region, i.e. `self.range.end <= sp <=
+    /// self.range.start`. This function also aliases the unused part of the region, which
+    /// is considered to be Undefined Behaviour if `sp` belongs to the currently active
+    /// stack. Do not use if you care about such things.
+    pub unsafe fn painted_binary(&self, sp: *mut u32) -> u32 {
+        // Safety: we should be able to read anywhere in the region using this,
+        // but this is considered UB because we are aliasing memory out of nowhere.
+        // Will probably still work though.
+        let slice = unsafe {
+            &*core::ptr::slice_from_raw_parts(self.range.end, self.free(sp) as usize / 4)
+        };
+        (slice.partition_point(|&word| word == STACK_PAINT_VALUE) * size_of::<u32>()) as u32
+    }
+}
+
 /// The number of bytes that are reserved for the stack at compile time.
 #[inline]
 pub const fn stack_size() -> u32 {
-    // Safety: start >= end. If this is not the case your linker did something wrong.
-    (unsafe { stack().start.byte_offset_from_unsigned(stack().end) }) as u32
+    StackRegion::new(stack()).size()
 }
 
 /// The number of bytes of the stack that are currently in use.
 #[inline]
 pub fn current_stack_in_use() -> u32 {
-    // Safety: start >= end. If this is not the case your linker did something wrong.
-    (unsafe { stack().start.byte_offset_from_unsigned(current_stack_ptr()) }) as u32
+    // Safety: current_stack_ptr() always points into stack().
+    unsafe { StackRegion::new(stack()).in_use(current_stack_ptr()) }
 }
 
 /// The number of bytes of the stack that are currently free.
@@ -58,7 +228,8 @@ pub fn current_stack_in_use() -> u32 {
 /// If the stack has overflowed, this function returns 0.
 #[inline]
 pub fn current_stack_free() -> u32 {
-    stack_size().saturating_sub(current_stack_in_use())
+    // Safety: current_stack_ptr() always points into stack().
+    unsafe { StackRegion::new(stack()).free(current_stack_ptr()) }
 }
 
 /// What fraction of the stack is currently in use.
@@ -67,6 +238,35 @@ pub fn current_stack_fraction() -> f32 {
     current_stack_in_use() as f32 / stack_size() as f32
 }
 
+/// Whether fewer than `red_zone_bytes` of stack remain free.
+///
+/// A cheap, *O(1)* alternative to the painting scan, meant to be placed at known-deep call
+/// sites (deserialization, recursive parsers) to check headroom before committing to the
+/// call.
+#[inline]
+pub fn stack_within_redzone(red_zone_bytes: u32) -> bool {
+    current_stack_free() < red_zone_bytes
+}
+
+/// Runs `f`, but first checks whether fewer than `red_zone_bytes` of stack remain free,
+/// and if so calls `on_low` with the current free byte count before running `f`.
+///
+/// Unlike `stacker`, this crate cannot grow the stack on demand, but `on_low` gives the
+/// caller a deterministic hook to panic, log, or reject a recursive request gracefully
+/// once the configured headroom is exhausted.
+#[inline]
+pub fn with_redzone_check<R>(
+    red_zone_bytes: u32,
+    on_low: impl FnOnce(u32),
+    f: impl FnOnce() -> R,
+) -> R {
+    let free = current_stack_free();
+    if free < red_zone_bytes {
+        on_low(free);
+    }
+    f()
+}
+
 /// Paint the part of the stack that is currently not in use.
 ///
 /// **Note:** this can take some time, and an ISR could possibly interrupt this process,
@@ -78,18 +278,8 @@ pub fn current_stack_fraction() -> f32 {
 /// even the parts that still have the [STACK_PAINT_VALUE].
 #[inline(never)]
 pub fn repaint_stack() {
-    unsafe {
-        asm!(
-            "0:",
-            "cmp sp, r0",
-            "bls 1f",
-            "stmia r0!, {{r1}}",
-            "b 0b",
-            "1:",
-            in("r0") stack().end,
-            in("r1") STACK_PAINT_VALUE,
-        )
-    };
+    // Safety: current_stack_ptr() always points into stack().
+    unsafe { StackRegion::new(stack()).paint(current_stack_ptr()) };
 }
 
 /// Finds the number of bytes that have not been overwritten on the stack since the last repaint.
@@ -98,26 +288,8 @@ pub fn repaint_stack() {
 ///
 /// Runs in *O(n)* where *n* is the size of the stack.
 pub fn stack_painted() -> u32 {
-    let res: *const u32;
-    unsafe {
-        asm!(
-            "0:",
-            "cmp sp, {ptr}",
-            "bls 1f",
-            "ldr {value}, [{ptr}]",
-            "cmp {value}, {paint}",
-            "bne 1f",
-            "add {ptr}, #4",
-            "b 0b",
-            "1:",
-            ptr = inout(reg) stack().end => res,
-            value = out(reg) _,
-            paint = in(reg) STACK_PAINT_VALUE,
-            options(nostack, readonly)
-        )
-    };
-    // Safety: res >= stack.end() because we start at stack.end()
-    (unsafe { res.byte_offset_from_unsigned(stack().end) }) as u32
+    // Safety: current_stack_ptr() always points into stack().
+    unsafe { StackRegion::new(stack()).painted(current_stack_ptr()) }
 }
 
 /// Finds the number of bytes that have not been overwritten on the stack since the last repaint using binary search.
@@ -136,11 +308,77 @@ pub fn stack_painted() -> u32 {
 /// This function aliases the inactive stack, which is considered to be Undefined Behaviour.
 /// Do not use if you care about such things.
 pub unsafe fn stack_painted_binary() -> u32 {
-    // Safety: we should be able to read anywhere on the stack using this,
-    // but this is considered UB because we are aliasing memory out of nowhere.
-    // Will probably still work though.
-    let slice = unsafe {
-        &*core::ptr::slice_from_raw_parts(stack().end, current_stack_free() as usize / 4)
-    };
-    (slice.partition_point(|&word| word == STACK_PAINT_VALUE) * size_of::<u32>()) as u32
+    unsafe { StackRegion::new(stack()).painted_binary(current_stack_ptr()) }
+}
+
+/// A stateful painter that tracks the stack pointer observed at its previous call, so
+/// repeated calls only repaint the part of a [StackRegion] reclaimed since then, instead
+/// of repainting the whole region every time like [StackRegion::paint] does.
+///
+/// **Note:** because it only ever paints the slack reclaimed since the last call, a fresh
+/// `StackPainter` does *not* paint the region's deepest-ever-used point down to its end —
+/// call [reset](Self::reset) first if you need that, e.g. right after startup when the
+/// region is entirely unused.
+pub struct StackPainter {
+    region: StackRegion,
+    watermark: *mut u32,
+}
+
+impl StackPainter {
+    /// Creates a painter for `region` with no watermark yet, equivalent to having last
+    /// observed the stack pointer at the very top of the region.
+    #[inline]
+    pub const fn new(region: StackRegion) -> Self {
+        let watermark = region.range.start;
+        Self { region, watermark }
+    }
+
+    /// The stack pointer this painter last observed, i.e. the boundary up to which it has
+    /// already painted. Compare this against [StackRegion::painted]/
+    /// [StackRegion::painted_binary] to tell whether this painter needs a [reset](Self::reset):
+    /// if those report a deeper high-water mark than `watermark()`, something used or
+    /// painted memory outside of this painter's bookkeeping.
+    #[inline]
+    pub const fn watermark(&self) -> *mut u32 {
+        self.watermark
+    }
+
+    /// Forces a full repaint of the unused part of the region below `sp`, and resets the
+    /// watermark to `sp`.
+    ///
+    /// Also call this whenever [StackRegion::painted]/[StackRegion::painted_binary]
+    /// report a deeper high-water mark than [watermark](Self::watermark): that means
+    /// something painted, or the stack used, memory outside of this painter's bookkeeping,
+    /// so the gap may contain stale data this painter doesn't know to repaint.
+    ///
+    /// # Safety
+    /// `sp` must be a pointer into this painter's region, i.e. `self.region`'s `end <= sp
+    /// <= start`. A `sp` above the region's `start` turns this into an unbounded,
+    /// out-of-bounds write, same as [StackRegion::paint].
+    pub unsafe fn reset(&mut self, sp: *mut u32) {
+        // Safety: sp lies within self.region, upheld by the caller per this function's
+        // Safety contract.
+        unsafe { self.region.paint(sp) };
+        self.watermark = sp;
+    }
+
+    /// Paints only the part of the region between the stack pointer observed at the
+    /// previous call to this function (or at [new](Self::new)/[reset](Self::reset)) and
+    /// `sp`, the stack pointer saved for it, when `sp` lies above that previous one.
+    /// Stack that was used below the previous stack pointer and released again between two
+    /// calls is not repainted; see [watermark](Self::watermark) and [reset](Self::reset).
+    ///
+    /// Runs in *O(usage-delta)*, the distance between those two stack pointers,
+    /// instead of *O(n)* for the whole region like [StackRegion::paint].
+    ///
+    /// # Safety
+    /// `sp` must be a pointer into this painter's region, i.e. `self.region`'s `end <= sp
+    /// <= start`. A `sp` above the region's `start` turns this into an unbounded,
+    /// out-of-bounds write, same as [StackRegion::paint].
+    pub unsafe fn repaint_incremental(&mut self, sp: *mut u32) {
+        if sp > self.watermark {
+            paint_range(self.watermark, sp);
+        }
+        self.watermark = sp;
+    }
 }
@@ -0,0 +1,83 @@
+//! MPU-backed guard region at the bottom of the stack.
+//!
+//! Configures a small MPU region anchored at [`stack().end`](stack) that denies
+//! unprivileged access, so that any unprivileged write crossing the bottom of the stack
+//! raises a `MemManage` fault *before* it corrupts whatever follows (`.bss`, the heap,
+//! ...), rather than being noticed only later by [`stack_painted`](crate::stack_painted).
+//! This pairs naturally with the painting functions: the guard catches hard overflows,
+//! painting gives watermark telemetry.
+//!
+//! On ARMv7-M this also blocks *privileged* accesses (AP=`0b000` denies every privilege
+//! level), so it guards the typical bare-metal firmware case of Thread mode running
+//! privileged. On ARMv8-M there is no per-region AP encoding that denies privileged code —
+//! AP=`0b00` only denies unprivileged access, privileged code can always read/write an
+//! enabled region. A true all-privilege guard on ARMv8-M instead requires leaving the
+//! guard address unmapped by *any* enabled region and clearing `MPU_CTRL.PRIVDEFENA` so
+//! the implicit background region no longer covers it, which is a global change to the
+//! default memory map far outside the scope of a single region slot. This module does
+//! *not* do that: on ARMv8-M, [`install_stack_guard`] only catches overflows from
+//! unprivileged code, e.g. an unprivileged RTOS task stack. If your firmware runs Thread
+//! mode privileged on an ARMv8-M core, this guard will not fire for it.
+//!
+//! The region base must be aligned to its size, so `_stack_end` must be aligned to
+//! `guard_size` in your linker script.
+
+use cortex_m::peripheral::MPU;
+
+use crate::stack;
+
+/// Installs a `guard_size`-byte MPU region at [`stack().end`](stack) in MPU region slot
+/// `region`, denying unprivileged access (and, on ARMv7-M only, privileged access too —
+/// see the module docs for why ARMv8-M cannot deny privileged access this way).
+///
+/// `guard_size` must be a power of two of at least 32 bytes, and [`stack().end`](stack)
+/// must already be aligned to it (enforce this via your linker script).
+///
+/// # Safety
+/// Overwrites whatever configuration `region` currently holds; the caller must ensure no
+/// other code relies on that slot.
+pub unsafe fn install_stack_guard(mpu: &mut MPU, region: u8, guard_size: u32) {
+    debug_assert!(guard_size.is_power_of_two() && guard_size >= 32);
+
+    let base = stack().end as u32;
+    debug_assert_eq!(
+        base % guard_size,
+        0,
+        "stack().end must be aligned to guard_size"
+    );
+
+    #[cfg(not(feature = "mpu-guard-armv8m"))]
+    unsafe {
+        // ARMv7-M: RBAR selects the region, RASR encodes SIZE/AP/XN and enables it.
+        // SIZE field encodes 2^(n+1) bytes; AP=0b000 denies all access; XN=1.
+        let size_field = guard_size.trailing_zeros() - 1;
+        mpu.rnr.write(region as u32);
+        mpu.rbar.write(base);
+        mpu.rasr
+            .write((1 << 28) | (size_field << 1) | (1 << 0));
+    }
+
+    #[cfg(feature = "mpu-guard-armv8m")]
+    unsafe {
+        // ARMv8-M: RBAR encodes the base address (AP=0b00 denies unprivileged access,
+        // XN=1), RLAR encodes the limit address and enables the region. Unlike ARMv7-M,
+        // AP=0b00 still lets privileged code through; see the module docs.
+        let limit = base + guard_size - 1;
+        mpu.rnr.write(region as u32);
+        mpu.rbar.write((base & !0x1F) | (0b00 << 1) | 1);
+        mpu.rlar.write((limit & !0x1F) | 1);
+    }
+}
+
+/// Disables the MPU region previously configured by [`install_stack_guard`].
+pub fn remove_stack_guard(mpu: &mut MPU, region: u8) {
+    unsafe {
+        mpu.rnr.write(region as u32);
+
+        #[cfg(not(feature = "mpu-guard-armv8m"))]
+        mpu.rasr.write(0);
+
+        #[cfg(feature = "mpu-guard-armv8m")]
+        mpu.rlar.write(0);
+    }
+}
@@ -0,0 +1,78 @@
+//! Hardware stack-limit support for ARMv8-M cores.
+//!
+//! ARMv8-M has dedicated stack-limit registers (`PSPLIM`/`MSPLIM`) that the core checks on
+//! every `SP`-decrementing instruction, trapping into a `UsageFault` with the `STKOF` bit
+//! set the instant the stack pointer would move below the programmed limit. This catches
+//! overflow in hardware, for free, instead of relying on the O(n) painting scan the rest of
+//! this crate uses.
+//!
+//! Only available on ARMv8-M cores that implement these registers (not every Baseline
+//! profile, and not ARMv6-M/ARMv7-M at all). Only enable the `arm-stack-limit` feature when
+//! targeting such a core; on anything else these functions will not assemble.
+
+use core::arch::asm;
+
+use crate::stack;
+
+/// Arms the hardware stack limit for the active stack at [`stack().end`](stack).
+///
+/// Determines whether the active stack is MSP or PSP and writes the corresponding limit
+/// register. From then on, any instruction that would decrement `SP` below this address
+/// raises a `UsageFault` with the `STKOF` bit set, instead of silently corrupting whatever
+/// follows the stack.
+#[inline]
+pub fn arm_stack_limit() {
+    let limit = stack().end as u32;
+    unsafe {
+        if using_psp() {
+            asm!("msr psplim, {}", in(reg) limit);
+        } else {
+            asm!("msr msplim, {}", in(reg) limit);
+        }
+    }
+}
+
+/// Reads back the stack limit currently armed for the active stack (MSP or PSP).
+#[inline]
+pub fn stack_limit() -> *mut u32 {
+    let limit: u32;
+    unsafe {
+        if using_psp() {
+            asm!("mrs {}, psplim", out(reg) limit);
+        } else {
+            asm!("mrs {}, msplim", out(reg) limit);
+        }
+    }
+    limit as *mut u32
+}
+
+/// Disarms the hardware stack limit for the active stack, falling back to software-only
+/// (painting-based) overflow detection.
+#[inline]
+pub fn disarm_stack_limit() {
+    unsafe {
+        if using_psp() {
+            asm!("msr psplim, {}", in(reg) 0u32);
+        } else {
+            asm!("msr msplim, {}", in(reg) 0u32);
+        }
+    }
+}
+
+/// Whether the active stack is PSP (`true`) or MSP (`false`).
+///
+/// Reads `CONTROL.SPSEL` (bit 1), which selects PSP in Thread mode. Exception handlers
+/// always run on MSP regardless of `SPSEL`, so in Handler mode (`IPSR != 0`) this always
+/// reports `false`.
+#[inline]
+fn using_psp() -> bool {
+    let ipsr: u32;
+    unsafe { asm!("mrs {}, ipsr", out(reg) ipsr) };
+    if ipsr & 0x1FF != 0 {
+        return false;
+    }
+
+    let control: u32;
+    unsafe { asm!("mrs {}, control", out(reg) control) };
+    control & 0b10 != 0
+}